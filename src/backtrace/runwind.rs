@@ -1,27 +1,253 @@
-use std::{ops::Range, rc::Rc, sync::Mutex};
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Mutex, RwLock},
+};
 
 use addr2line::{
     gimli::{EndianReader, RunTimeEndian},
+    object::Object,
     Context,
 };
 use once_cell::sync::Lazy;
 use runwind::{CacheNative, MustNotAllocateDuringUnwind, UnwindRegsNative, Unwinder};
 
-static CONTEXTS: Lazy<Vec<(usize, Range<usize>, Mutex<SendContext>)>> = Lazy::new(|| {
-    let mut contexts = Vec::new();
-    for obj in runwind::get_objects() {
-        let context = Context::new(obj.obj_file()).unwrap();
-        contexts.push((
-            obj.base_addr(),
-            obj.text_svma(),
-            Mutex::new(SendContext(context)),
-        ));
-    }
-    contexts.sort_by_key(|(base_addr, _, _)| *base_addr);
-    contexts
+type RunwindContext = Context<EndianReader<RunTimeEndian, Rc<[u8]>>>;
+
+/// Registry of per-object addr2line contexts, kept sorted by base address so
+/// frames can be located with a binary search. Unlike a one-shot `Lazy`, it can
+/// be refreshed to pick up libraries that were loaded after the first scan
+/// (e.g. via `dlopen`).
+static REGISTRY: Lazy<RwLock<Registry>> = Lazy::new(|| {
+    let mut registry = Registry {
+        contexts: Vec::new(),
+        generation: 0,
+    };
+    registry.load_objects();
+    RwLock::new(registry)
 });
 
-struct SendContext(Context<EndianReader<RunTimeEndian, Rc<[u8]>>>);
+struct Registry {
+    contexts: Vec<(usize, Range<usize>, Mutex<SendContext>)>,
+    /// Bumped every time new objects are registered, so callers can tell
+    /// whether a refresh actually changed the table.
+    generation: u64,
+}
+
+impl Registry {
+    /// Enumerate the currently loaded objects and register any whose base
+    /// address we haven't seen yet, keeping the table sorted for lookup.
+    fn load_objects(&mut self) {
+        let mut changed = false;
+        for obj in runwind::get_objects() {
+            if self
+                .contexts
+                .iter()
+                .any(|(base_addr, _, _)| *base_addr == obj.base_addr())
+            {
+                continue;
+            }
+            let context = build_context(&obj);
+            self.contexts.push((
+                obj.base_addr(),
+                obj.text_svma(),
+                Mutex::new(SendContext(context)),
+            ));
+            changed = true;
+        }
+        if changed {
+            self.contexts.sort_by_key(|(base_addr, _, _)| *base_addr);
+            self.generation += 1;
+        }
+    }
+
+    /// The end (runtime address) of the text range of the last registered
+    /// object, i.e. the highest address any known mapping can resolve.
+    fn max_addr(&self) -> Option<usize> {
+        self.contexts
+            .last()
+            .map(|(base_addr, text_range, _)| base_addr + text_range.end)
+    }
+}
+
+/// Re-enumerate loaded objects and register any that appeared since the last
+/// scan (e.g. shared libraries brought in by `dlopen`). Safe to call at any
+/// time; it preserves the sorted invariant used for address lookup. Returns the
+/// registry generation, which is bumped whenever new objects are added, so
+/// callers can tell whether a refresh changed the table.
+pub fn refresh_objects() -> u64 {
+    let mut registry = REGISTRY.write().unwrap();
+    registry.load_objects();
+    registry.generation
+}
+
+/// Build an addr2line context for an object, preferring debug info shipped in a
+/// separate file (`.gnu_debuglink` or a build-id match) whenever one is found;
+/// whether the in-memory object is stripped is not checked. Falls back to the
+/// object itself when no supplementary file is found or usable.
+fn build_context(obj: &runwind::Object) -> RunwindContext {
+    let file = obj.obj_file();
+    let mut debug = find_separate_debug(file, obj.path());
+    // As a last resort, ask a debuginfod server for the debug info. This only
+    // runs when the registry is built or refreshed (from `resolve_symbol` or
+    // `refresh_objects`), never on the signal-handler unwinding path.
+    #[cfg(feature = "debuginfod")]
+    if debug.is_none() {
+        if let Ok(Some(build_id)) = file.build_id() {
+            debug = fetch_from_debuginfod(build_id);
+        }
+    }
+    if let Some(debug) = debug {
+        if let Ok(debug_file) = addr2line::object::File::parse(&debug[..]) {
+            if let Ok(context) = Context::new(&debug_file) {
+                return context;
+            }
+        }
+    }
+    Context::new(file).unwrap()
+}
+
+/// Fetch debug info for `build_id` from a debuginfod server listed in
+/// `DEBUGINFOD_URLS`, caching the result on disk. Returns the downloaded file
+/// contents, serving a previously cached copy when one exists.
+#[cfg(feature = "debuginfod")]
+fn fetch_from_debuginfod(build_id: &[u8]) -> Option<Vec<u8>> {
+    use std::io::Read;
+
+    if build_id.is_empty() {
+        return None;
+    }
+    let id = hex_encode(build_id);
+
+    // Serve from the on-disk cache if we've fetched this build-id before.
+    let cache_path = debuginfod_cache_path(&id);
+    if let Ok(data) = fs::read(&cache_path) {
+        return Some(data);
+    }
+
+    let urls = std::env::var("DEBUGINFOD_URLS").ok()?;
+    for base in urls.split_whitespace() {
+        let url = format!("{}/buildid/{id}/debuginfo", base.trim_end_matches('/'));
+        let Ok(resp) = ureq::get(&url).call() else {
+            continue;
+        };
+        let mut data = Vec::new();
+        if resp.into_reader().read_to_end(&mut data).is_err() || data.is_empty() {
+            continue;
+        }
+        if let Some(parent) = cache_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = fs::write(&cache_path, &data);
+        return Some(data);
+    }
+    None
+}
+
+/// Location of the debuginfod client cache, honouring `DEBUGINFOD_CACHE_PATH`
+/// and falling back to `$HOME/.cache/debuginfod_client`.
+#[cfg(feature = "debuginfod")]
+fn debuginfod_cache_path(id: &str) -> PathBuf {
+    let base = std::env::var_os("DEBUGINFOD_CACHE_PATH")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".cache/debuginfod_client"))
+        })
+        .unwrap_or_else(|| std::env::temp_dir().join("debuginfod_client"));
+    base.join(id).join("debuginfo")
+}
+
+/// Look for a separate debug file for the object at `obj_path`, returning its
+/// contents.
+///
+/// The build-id is looked up only as `/usr/lib/debug/.build-id/xx/rest.debug`;
+/// the `.gnu_debuglink` name is then looked up under the object's directory,
+/// its `.debug/` subdirectory and the mirror of that directory under
+/// `/usr/lib/debug`. A candidate is only accepted once its build-id or CRC32
+/// (debuglink) matches.
+fn find_separate_debug<'data, O: Object<'data>>(
+    file: &O,
+    obj_path: Option<&Path>,
+) -> Option<Vec<u8>> {
+    let obj_dir = obj_path.and_then(|p| p.parent());
+
+    // Prefer the build-id, which carries its own cryptographic identity.
+    if let Ok(Some(build_id)) = file.build_id() {
+        if build_id.len() >= 2 {
+            let hex = hex_encode(build_id);
+            let (dir, rest) = hex.split_at(2);
+            let path = Path::new("/usr/lib/debug/.build-id")
+                .join(dir)
+                .join(format!("{rest}.debug"));
+            if let Some(data) = read_if_build_id_matches(&path, build_id) {
+                return Some(data);
+            }
+        }
+    }
+
+    // Otherwise follow `.gnu_debuglink`, verifying the advertised CRC32.
+    if let Ok(Some((name, crc))) = file.gnu_debuglink() {
+        let name = Path::new(std::str::from_utf8(name).ok()?);
+        if let Some(dir) = obj_dir {
+            let candidates = [
+                dir.join(name),
+                dir.join(".debug").join(name),
+                Path::new("/usr/lib/debug").join(dir.strip_prefix("/").unwrap_or(dir)).join(name),
+            ];
+            for path in candidates {
+                if let Some(data) = read_if_crc_matches(&path, crc) {
+                    return Some(data);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+fn read_if_build_id_matches(path: &Path, build_id: &[u8]) -> Option<Vec<u8>> {
+    let data = fs::read(path).ok()?;
+    let file = addr2line::object::File::parse(&data[..]).ok()?;
+    match file.build_id() {
+        Ok(Some(id)) if id == build_id => Some(data),
+        _ => None,
+    }
+}
+
+fn read_if_crc_matches(path: &Path, crc: u32) -> Option<Vec<u8>> {
+    let data = fs::read(path).ok()?;
+    if crc32(&data) == crc {
+        Some(data)
+    } else {
+        None
+    }
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        s.push_str(&format!("{b:02x}"));
+    }
+    s
+}
+
+/// CRC-32 (IEEE 802.3) as used by `.gnu_debuglink`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+struct SendContext(RunwindContext);
 
 unsafe impl Send for SendContext {}
 
@@ -38,7 +264,42 @@ pub struct Frame {
 
 pub struct Symbol {
     name: String,
+    raw_name: String,
     addr: *mut libc::c_void,
+    file: Option<PathBuf>,
+    line: Option<u32>,
+}
+
+impl Symbol {
+    /// Build a symbol from a raw, possibly mangled name, demangling it for
+    /// display while keeping the original form around for callers that need it.
+    fn new(raw_name: String, addr: *mut libc::c_void, file: Option<PathBuf>, line: Option<u32>) -> Self {
+        Symbol {
+            name: demangle(&raw_name),
+            raw_name,
+            addr,
+            file,
+            line,
+        }
+    }
+
+    /// The raw, still-mangled name as emitted by the debug info.
+    pub fn raw_name(&self) -> &str {
+        &self.raw_name
+    }
+}
+
+/// Demangle a symbol name, trying the Rust schemes (legacy and v0) first and
+/// falling back to the Itanium C++ scheme. The raw name is returned unchanged
+/// when no scheme applies.
+fn demangle(name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return format!("{demangled:#}");
+    }
+    if let Ok(sym) = cpp_demangle::Symbol::new(name) {
+        return sym.to_string();
+    }
+    name.to_string()
 }
 
 impl super::Frame for Frame {
@@ -49,66 +310,70 @@ impl super::Frame for Frame {
     }
 
     fn resolve_symbol<F: FnMut(&Self::S)>(&self, mut cb: F) {
-        match CONTEXTS.binary_search_by_key(&self.addr, |(base_addr, _, _)| *base_addr) {
+        // If the address falls past the end of every known mapping, a library
+        // may have been loaded at runtime since the last scan; refresh once
+        // before concluding the frame is unknown.
+        if REGISTRY
+            .read()
+            .unwrap()
+            .max_addr()
+            .map_or(true, |max| self.addr > max)
+        {
+            refresh_objects();
+        }
+        let registry = REGISTRY.read().unwrap();
+        let contexts = &registry.contexts;
+        match contexts.binary_search_by_key(&self.addr, |(base_addr, _, _)| *base_addr) {
             Ok(_) => {
-                cb(&Symbol {
-                    name: "<unknown>".to_string(),
-                    addr: self.addr as _,
-                });
+                cb(&Symbol::new("<unknown>".to_string(), self.addr as _, None, None));
                 return;
             }
             Err(idx) => {
                 if idx == 0 {
-                    cb(&Symbol {
-                        name: "<unknown>".to_string(),
-                        addr: self.addr as _,
-                    });
+                    cb(&Symbol::new("<unknown>".to_string(), self.addr as _, None, None));
                     return;
                 }
-                let (base_addr, text_range, context) = &CONTEXTS[idx - 1];
+                let (base_addr, text_range, context) = &contexts[idx - 1];
                 let svma = self.addr - base_addr;
                 if !text_range.contains(&svma) {
-                    cb(&Symbol {
-                        name: "<unknown>".to_string(),
-                        addr: self.addr as _,
-                    });
+                    cb(&Symbol::new("<unknown>".to_string(), self.addr as _, None, None));
                     return;
                 }
                 let context = context.lock().unwrap();
                 let mut frames = match context.0.find_frames(svma as u64) {
                     Ok(frames) => frames,
                     Err(_) => {
-                        cb(&Symbol {
-                            name: "<unknown>".to_string(),
-                            addr: self.addr as _,
-                        });
+                        cb(&Symbol::new("<unknown>".to_string(), self.addr as _, None, None));
                         return;
                     }
                 };
+                // `find_frames` yields one frame per inline level (innermost
+                // first, ending with the real out-of-line function), so fire
+                // the callback once per level instead of stopping at the first.
+                let mut any = false;
                 loop {
                     match frames.next() {
                         Ok(Some(frame)) => {
-                            cb(&Symbol {
-                                name: frame
-                                    .function
-                                    .as_ref()
-                                    .and_then(|f| f.raw_name().ok())
-                                    .map(|s| s.to_string())
-                                    .unwrap_or_else(|| "<unknown>".to_string()),
-                                addr: self.addr as _,
-                            });
-                            return;
+                            any = true;
+                            let (file, line) = match frame.location.as_ref() {
+                                Some(loc) => (loc.file.map(PathBuf::from), loc.line),
+                                None => (None, None),
+                            };
+                            let name = frame
+                                .function
+                                .as_ref()
+                                .and_then(|f| f.raw_name().ok())
+                                .map(|s| s.to_string())
+                                .unwrap_or_else(|| "<unknown>".to_string());
+                            cb(&Symbol::new(name, self.addr as _, file, line));
                         }
                         Ok(None) => break,
-                        Err(_e) => {
-                            cb(&Symbol {
-                                name: "<unknown>".to_string(),
-                                addr: self.addr as _,
-                            });
-                            return;
-                        }
+                        Err(_e) => break,
                     }
                 }
+                if !any {
+                    cb(&Symbol::new("<unknown>".to_string(), self.addr as _, None, None));
+                }
             }
         };
     }
@@ -128,11 +393,11 @@ impl super::Symbol for Symbol {
     }
 
     fn lineno(&self) -> Option<u32> {
-        None
+        self.line
     }
 
     fn filename(&self) -> Option<std::path::PathBuf> {
-        None
+        self.file.clone()
     }
 }
 
@@ -141,10 +406,27 @@ impl super::Trace for Trace {
 
     fn trace<F: FnMut(&Self::Frame) -> bool>(&mut self, ucontext: *mut libc::c_void, mut cb: F) {
         let ucontext: *mut libc::ucontext_t = ucontext as *mut libc::ucontext_t;
-        let ip = unsafe { (*ucontext).uc_mcontext.gregs[libc::REG_RIP as usize] as u64 };
-        let sp = unsafe { (*ucontext).uc_mcontext.gregs[libc::REG_RSP as usize] as u64 };
-        let bp = unsafe { (*ucontext).uc_mcontext.gregs[libc::REG_RBP as usize] as u64 };
-        let regs = UnwindRegsNative::new(ip, sp, bp);
+
+        #[cfg(target_arch = "x86_64")]
+        let (ip, regs) = {
+            let ip = unsafe { (*ucontext).uc_mcontext.gregs[libc::REG_RIP as usize] as u64 };
+            let sp = unsafe { (*ucontext).uc_mcontext.gregs[libc::REG_RSP as usize] as u64 };
+            let bp = unsafe { (*ucontext).uc_mcontext.gregs[libc::REG_RBP as usize] as u64 };
+            (ip, UnwindRegsNative::new(ip, sp, bp))
+        };
+
+        #[cfg(target_arch = "aarch64")]
+        let (ip, regs) = {
+            let mcontext = unsafe { &(*ucontext).uc_mcontext };
+            let ip = mcontext.pc;
+            let sp = mcontext.sp;
+            // x29 is the frame pointer, x30 the link register.
+            let fp = mcontext.regs[29];
+            let lr = mcontext.regs[30];
+            // pc is supplied separately via `iter_frames_with_regs` below.
+            (ip, UnwindRegsNative::new(lr, sp, fp))
+        };
+
         let mut iter = self
             .unwinder
             .iter_frames_with_regs(ip as usize, regs, &mut self.cache);